@@ -0,0 +1,61 @@
+//! Defines the `Middleware` and `NewMiddleware` traits, plus the `Pipeline` and `PipelineSet`
+//! machinery used to combine `Middleware` into a chain that sits in front of a `Handler`.
+
+use std::error::Error;
+
+use handler::HandlerFuture;
+use hyper::server::{Request, Response};
+use state::State;
+
+pub mod pipeline;
+pub mod pipeline_set;
+#[cfg(feature = "tower-compat")]
+pub mod tower;
+
+/// A type that acts as a Gotham middleware, and can be wrapped around a `Handler`, or another
+/// `Middleware`, via a `Pipeline`.
+///
+/// `Middleware` values are invoked strictly in the order they're added to a `Pipeline`, and are
+/// given the opportunity to alter the `State` and `Request` before passing them along to the next
+/// `Middleware` (or the `Handler`) via the `chain` function.
+///
+/// A `Middleware` runs in up to three phases per request: `call`, which wraps the request on the
+/// way in and decides whether (and how) to invoke `chain`; `on_response`, invoked after the inner
+/// chain's future resolves successfully, for observing or mutating the outgoing `Response`; and
+/// `on_finish`, invoked unconditionally once the inner chain's future has settled, for cleanup
+/// such as request timing or metrics. `on_response` and `on_finish` are optional and default to
+/// doing nothing.
+pub trait Middleware {
+    /// Invokes this `Middleware`, and the remainder of the `Pipeline` it belongs to, via `chain`.
+    ///
+    /// Takes `&self` rather than `self` so that a `Pipeline` can keep running a `Middleware`'s
+    /// `on_response`/`on_finish` hooks off the same instance once the future returned here
+    /// settles, without requiring every `Middleware` to be `Clone`.
+    fn call<Chain>(&self, state: State, req: Request, chain: Chain) -> Box<HandlerFuture>
+        where Chain: FnOnce(State, Request) -> Box<HandlerFuture> + Send + 'static;
+
+    /// Invoked after the inner chain's future has resolved successfully, giving this
+    /// `Middleware` an opportunity to observe or mutate the outgoing `Response` before it
+    /// continues back up the `Pipeline`. Runs in reverse order: the `Middleware` closest to the
+    /// `Handler` sees the `Response` first.
+    fn on_response(&self, _state: &mut State, _response: &mut Response) {}
+
+    /// Invoked once the inner chain's future has settled, whether it resolved successfully or
+    /// with an error. Like `on_response`, runs in reverse order, and always runs regardless of
+    /// the outcome.
+    fn on_finish(&self, _state: &mut State) {}
+}
+
+/// A type which is used to spawn a new `Middleware` value. Implementors are provided to a
+/// `PipelineBuilder` and used to create a `Middleware` instance for each request.
+pub trait NewMiddleware: Sync {
+    /// The type of `Middleware` created by the `NewMiddleware`.
+    type Instance: Middleware;
+
+    /// The error returned when this `NewMiddleware` fails to construct its `Middleware`
+    /// instance, e.g. a failed DB pool checkout or TLS context setup.
+    type Error: Error + Send + 'static;
+
+    /// Create and return a new `Middleware` value.
+    fn new_middleware(&self) -> Result<Self::Instance, Self::Error>;
+}
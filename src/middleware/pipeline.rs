@@ -1,5 +1,7 @@
 //! Defines types for a middleware pipeline
 
+use std::error;
+use std::fmt;
 use std::io;
 use middleware::{Middleware, NewMiddleware};
 use handler::{NewHandler, Handler, HandlerFuture};
@@ -55,7 +57,7 @@ use futures::{future, Future};
 /// impl Middleware for MiddlewareOne {
 ///     // Implementation elided.
 ///     // Appends `1` to `MiddlewareData.vec`
-/// #     fn call<Chain>(self, mut state: State, req: Request, chain: Chain) -> Box<HandlerFuture>
+/// #     fn call<Chain>(&self, mut state: State, req: Request, chain: Chain) -> Box<HandlerFuture>
 /// #         where Chain: FnOnce(State, Request) -> Box<HandlerFuture> + Send + 'static
 /// #     {
 /// #         state.put(MiddlewareData { vec: vec![1] });
@@ -65,7 +67,8 @@ use futures::{future, Future};
 /// #
 /// # impl NewMiddleware for MiddlewareOne {
 /// #     type Instance = MiddlewareOne;
-/// #     fn new_middleware(&self) -> io::Result<MiddlewareOne> {
+/// #     type Error = io::Error;
+/// #     fn new_middleware(&self) -> Result<MiddlewareOne, io::Error> {
 /// #         Ok(self.clone())
 /// #     }
 /// # }
@@ -73,7 +76,7 @@ use futures::{future, Future};
 /// impl Middleware for MiddlewareTwo {
 ///     // Implementation elided.
 ///     // Appends `2` to `MiddlewareData.vec`
-/// #     fn call<Chain>(self, mut state: State, req: Request, chain: Chain) -> Box<HandlerFuture>
+/// #     fn call<Chain>(&self, mut state: State, req: Request, chain: Chain) -> Box<HandlerFuture>
 /// #         where Chain: FnOnce(State, Request) -> Box<HandlerFuture> + Send + 'static
 /// #     {
 /// #         state.borrow_mut::<MiddlewareData>().unwrap().vec.push(2);
@@ -83,7 +86,8 @@ use futures::{future, Future};
 /// #
 /// # impl NewMiddleware for MiddlewareTwo {
 /// #     type Instance = MiddlewareTwo;
-/// #     fn new_middleware(&self) -> io::Result<MiddlewareTwo> {
+/// #     type Error = io::Error;
+/// #     fn new_middleware(&self) -> Result<MiddlewareTwo, io::Error> {
 /// #         Ok(self.clone())
 /// #     }
 /// # }
@@ -91,7 +95,7 @@ use futures::{future, Future};
 /// impl Middleware for MiddlewareThree {
 ///     // Implementation elided.
 ///     // Appends `3` to `MiddlewareData.vec`
-/// #     fn call<Chain>(self, mut state: State, req: Request, chain: Chain) -> Box<HandlerFuture>
+/// #     fn call<Chain>(&self, mut state: State, req: Request, chain: Chain) -> Box<HandlerFuture>
 /// #         where Chain: FnOnce(State, Request) -> Box<HandlerFuture> + Send + 'static
 /// #     {
 /// #         state.borrow_mut::<MiddlewareData>().unwrap().vec.push(3);
@@ -101,7 +105,8 @@ use futures::{future, Future};
 /// #
 /// # impl NewMiddleware for MiddlewareThree {
 /// #     type Instance = MiddlewareThree;
-/// #     fn new_middleware(&self) -> io::Result<MiddlewareThree> {
+/// #     type Error = io::Error;
+/// #     fn new_middleware(&self) -> Result<MiddlewareThree, io::Error> {
 /// #         Ok(self.clone())
 /// #     }
 /// # }
@@ -169,6 +174,21 @@ impl<T> Pipeline<T>
             Err(e) => future::err((state, e.into())).boxed(),
         }
     }
+
+    /// Constructs a `PipelineInstance` and invokes it directly against `f`, without requiring a
+    /// `NewHandler`.
+    ///
+    /// Used by `PipelineSet` to chain several pipelines together ahead of a route's own
+    /// `Handler`, where there's no single `NewHandler` to hand to `Pipeline::call` until the last
+    /// pipeline in the chain has run.
+    pub(crate) fn call_internal<F>(&self, state: State, req: Request, f: F) -> Box<HandlerFuture>
+        where F: FnOnce(State, Request) -> Box<HandlerFuture> + Send + 'static
+    {
+        match self.builder.t.new_pipeline_instance() {
+            Ok(p) => p.call_recurse(state, req, f),
+            Err(e) => future::err((state, e.into())).boxed(),
+        }
+    }
 }
 
 /// Begins defining a new pipeline.
@@ -206,7 +226,7 @@ pub fn new_pipeline() -> PipelineBuilder<()> {
 /// # struct MiddlewareThree;
 /// #
 /// # impl Middleware for MiddlewareOne {
-/// #   fn call<Chain>(self, state: State, req: Request, chain: Chain) -> Box<HandlerFuture>
+/// #   fn call<Chain>(&self, state: State, req: Request, chain: Chain) -> Box<HandlerFuture>
 /// #       where Chain: FnOnce(State, Request) -> Box<HandlerFuture> + Send + 'static
 /// #   {
 /// #       chain(state, req)
@@ -215,13 +235,14 @@ pub fn new_pipeline() -> PipelineBuilder<()> {
 /// #
 /// # impl NewMiddleware for MiddlewareOne {
 /// #   type Instance = MiddlewareOne;
-/// #   fn new_middleware(&self) -> io::Result<MiddlewareOne> {
+/// #   type Error = io::Error;
+/// #   fn new_middleware(&self) -> Result<MiddlewareOne, io::Error> {
 /// #       Ok(self.clone())
 /// #   }
 /// # }
 /// #
 /// # impl Middleware for MiddlewareTwo {
-/// #   fn call<Chain>(self, state: State, req: Request, chain: Chain) -> Box<HandlerFuture>
+/// #   fn call<Chain>(&self, state: State, req: Request, chain: Chain) -> Box<HandlerFuture>
 /// #       where Chain: FnOnce(State, Request) -> Box<HandlerFuture> + Send + 'static
 /// #   {
 /// #       chain(state, req)
@@ -230,13 +251,14 @@ pub fn new_pipeline() -> PipelineBuilder<()> {
 /// #
 /// # impl NewMiddleware for MiddlewareTwo {
 /// #   type Instance = MiddlewareTwo;
-/// #   fn new_middleware(&self) -> io::Result<MiddlewareTwo> {
+/// #   type Error = io::Error;
+/// #   fn new_middleware(&self) -> Result<MiddlewareTwo, io::Error> {
 /// #       Ok(self.clone())
 /// #   }
 /// # }
 /// #
 /// # impl Middleware for MiddlewareThree {
-/// #   fn call<Chain>(self, state: State, req: Request, chain: Chain) -> Box<HandlerFuture>
+/// #   fn call<Chain>(&self, state: State, req: Request, chain: Chain) -> Box<HandlerFuture>
 /// #       where Chain: FnOnce(State, Request) -> Box<HandlerFuture> + Send + 'static
 /// #   {
 /// #       chain(state, req)
@@ -245,7 +267,8 @@ pub fn new_pipeline() -> PipelineBuilder<()> {
 /// #
 /// # impl NewMiddleware for MiddlewareThree {
 /// #   type Instance = MiddlewareThree;
-/// #   fn new_middleware(&self) -> io::Result<MiddlewareThree> {
+/// #   type Error = io::Error;
+/// #   fn new_middleware(&self) -> Result<MiddlewareThree, io::Error> {
 /// #       Ok(self.clone())
 /// #   }
 /// # }
@@ -308,6 +331,72 @@ impl<T> PipelineBuilder<T>
     }
 }
 
+/// The error produced when constructing a `PipelineInstance` fails because one of the
+/// `NewMiddleware` values in the chain failed to construct its `Middleware` instance.
+///
+/// `Middleware` carries the concrete error from the `NewMiddleware` at this position in the
+/// chain; `Chain` carries whatever error (itself a `PipelineInstanceError`, for all but the last
+/// position) came from the rest of the chain. This preserves both the original error and the
+/// position at which it occurred, rather than collapsing everything to an opaque `io::Error`.
+#[derive(Debug)]
+pub enum PipelineInstanceError<E, F> {
+    /// The `NewMiddleware` at this position in the chain failed to construct its `Middleware`.
+    Middleware(E),
+    /// Construction failed further along the chain.
+    Chain(F),
+}
+
+impl<E, F> fmt::Display for PipelineInstanceError<E, F>
+    where E: fmt::Display,
+          F: fmt::Display
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PipelineInstanceError::Middleware(ref e) => write!(f, "{}", e),
+            PipelineInstanceError::Chain(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl<E, F> error::Error for PipelineInstanceError<E, F>
+    where E: error::Error,
+          F: error::Error
+{
+    fn description(&self) -> &str {
+        match *self {
+            PipelineInstanceError::Middleware(ref e) => e.description(),
+            PipelineInstanceError::Chain(ref e) => e.description(),
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            PipelineInstanceError::Middleware(ref e) => Some(e),
+            PipelineInstanceError::Chain(ref e) => Some(e),
+        }
+    }
+}
+
+/// An uninhabited error type used to terminate the recursive `PipelineInstanceError` chain.
+///
+/// The `()` terminator of a pipeline can never fail to construct, so its `NewPipelineInstance`
+/// uses this as its `Error`, keeping the chain zero-cost for pipelines built entirely from
+/// infallible `NewMiddleware` implementations.
+#[derive(Debug)]
+pub enum Never {}
+
+impl fmt::Display for Never {
+    fn fmt(&self, _f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {}
+    }
+}
+
+impl error::Error for Never {
+    fn description(&self) -> &str {
+        match *self {}
+    }
+}
+
 /// A recursive type representing a pipeline, which is used to spawn a `PipelineInstance`.
 ///
 /// This type should never be implemented outside of Gotham, does not form part of the public API,
@@ -316,8 +405,12 @@ impl<T> PipelineBuilder<T>
 pub unsafe trait NewPipelineInstance: Sized {
     type Instance: PipelineInstance;
 
+    /// The error produced when one of the `NewMiddleware` values in this chain fails to
+    /// construct its `Middleware` instance. See `PipelineInstanceError`.
+    type Error: error::Error + Send + 'static;
+
     /// Create and return a new `PipelineInstance` value.
-    fn new_pipeline_instance(&self) -> io::Result<Self::Instance>;
+    fn new_pipeline_instance(&self) -> Result<Self::Instance, Self::Error>;
 }
 
 unsafe impl<T, U> NewPipelineInstance for (T, U)
@@ -326,21 +419,25 @@ unsafe impl<T, U> NewPipelineInstance for (T, U)
           U: NewPipelineInstance
 {
     type Instance = (T::Instance, U::Instance);
+    type Error = PipelineInstanceError<T::Error, U::Error>;
 
-    fn new_pipeline_instance(&self) -> io::Result<Self::Instance> {
+    fn new_pipeline_instance(&self) -> Result<Self::Instance, Self::Error> {
         // This works as a recursive `map` over the "list" of `NewMiddleware`, and is used in
         // creating the `Middleware` instances for serving a single request.
         //
         // The reversed order is preserved in the return value.
         let (ref nm, ref tail) = *self;
-        Ok((nm.new_middleware()?, tail.new_pipeline_instance()?))
+        let middleware = nm.new_middleware().map_err(PipelineInstanceError::Middleware)?;
+        let tail = tail.new_pipeline_instance().map_err(PipelineInstanceError::Chain)?;
+        Ok((middleware, tail))
     }
 }
 
 unsafe impl NewPipelineInstance for () {
     type Instance = ();
+    type Error = Never;
 
-    fn new_pipeline_instance(&self) -> io::Result<Self::Instance> {
+    fn new_pipeline_instance(&self) -> Result<Self::Instance, Self::Error> {
         // () marks the end of the list, so is returned as-is.
         Ok(())
     }
@@ -404,12 +501,33 @@ unsafe impl<T, U> PipelineInstance for (T, U)
         //  }
         //
         // The resulting function is called by `<() as PipelineInstance>::call_recurse`
-        p.call_recurse(state, request, move |state, req| m.call(state, req, f))
+        //
+        // `Middleware::call` only borrows `m`, so `m` is still owned here once it returns and can
+        // be moved into the `.then()` below to run its `on_response`/`on_finish` hooks as the
+        // future unwinds back out through the `Pipeline` &mdash; no `Clone` required. Because the
+        // wrapping happens at every level of this recursion, the innermost `Middleware` (closest
+        // to the `Handler`) sees its hooks run first, and the outermost `Middleware` last.
+        p.call_recurse(state, request, move |state, req| {
+            m.call(state, req, f)
+                .then(move |result| match result {
+                    Ok((mut state, mut response)) => {
+                        m.on_response(&mut state, &mut response);
+                        m.on_finish(&mut state);
+                        future::ok((state, response))
+                    }
+                    Err((mut state, e)) => {
+                        m.on_finish(&mut state);
+                        future::err((state, e))
+                    }
+                })
+                .boxed()
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::{Arc, Mutex};
     use super::*;
     use test::TestServer;
     use handler::NewHandlerService;
@@ -429,16 +547,16 @@ mod tests {
 
     impl NewMiddleware for Number {
         type Instance = Number;
+        type Error = io::Error;
 
-        fn new_middleware(&self) -> io::Result<Number> {
+        fn new_middleware(&self) -> Result<Number, io::Error> {
             Ok(self.clone())
         }
     }
 
     impl Middleware for Number {
-        fn call<Chain>(self, mut state: State, req: Request, chain: Chain) -> Box<HandlerFuture>
-            where Chain: FnOnce(State, Request) -> Box<HandlerFuture> + Send + 'static,
-                  Self: Sized
+        fn call<Chain>(&self, mut state: State, req: Request, chain: Chain) -> Box<HandlerFuture>
+            where Chain: FnOnce(State, Request) -> Box<HandlerFuture> + Send + 'static
         {
             state.put(self.clone());
             chain(state, req)
@@ -447,44 +565,46 @@ mod tests {
 
     impl StateData for Number {}
 
+    #[derive(Clone)]
     struct Addition {
         value: i32,
     }
 
     impl NewMiddleware for Addition {
         type Instance = Addition;
+        type Error = io::Error;
 
-        fn new_middleware(&self) -> io::Result<Addition> {
+        fn new_middleware(&self) -> Result<Addition, io::Error> {
             Ok(Addition { ..*self })
         }
     }
 
     impl Middleware for Addition {
-        fn call<Chain>(self, mut state: State, req: Request, chain: Chain) -> Box<HandlerFuture>
-            where Chain: FnOnce(State, Request) -> Box<HandlerFuture> + Send + 'static,
-                  Self: Sized
+        fn call<Chain>(&self, mut state: State, req: Request, chain: Chain) -> Box<HandlerFuture>
+            where Chain: FnOnce(State, Request) -> Box<HandlerFuture> + Send + 'static
         {
             state.borrow_mut::<Number>().unwrap().value += self.value;
             chain(state, req)
         }
     }
 
+    #[derive(Clone)]
     struct Multiplication {
         value: i32,
     }
 
     impl NewMiddleware for Multiplication {
         type Instance = Multiplication;
+        type Error = io::Error;
 
-        fn new_middleware(&self) -> io::Result<Multiplication> {
+        fn new_middleware(&self) -> Result<Multiplication, io::Error> {
             Ok(Multiplication { ..*self })
         }
     }
 
     impl Middleware for Multiplication {
-        fn call<Chain>(self, mut state: State, req: Request, chain: Chain) -> Box<HandlerFuture>
-            where Chain: FnOnce(State, Request) -> Box<HandlerFuture> + Send + 'static,
-                  Self: Sized
+        fn call<Chain>(&self, mut state: State, req: Request, chain: Chain) -> Box<HandlerFuture>
+            where Chain: FnOnce(State, Request) -> Box<HandlerFuture> + Send + 'static
         {
             state.borrow_mut::<Number>().unwrap().value *= self.value;
             chain(state, req)
@@ -515,4 +635,68 @@ mod tests {
         let buf = test_server.read_body(response).unwrap();
         assert_eq!(buf.as_slice(), "24".as_bytes());
     }
+
+    #[derive(Clone)]
+    struct Recorder {
+        name: &'static str,
+        log: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    impl NewMiddleware for Recorder {
+        type Instance = Recorder;
+        type Error = io::Error;
+
+        fn new_middleware(&self) -> Result<Recorder, io::Error> {
+            Ok(self.clone())
+        }
+    }
+
+    impl Middleware for Recorder {
+        fn call<Chain>(&self, state: State, req: Request, chain: Chain) -> Box<HandlerFuture>
+            where Chain: FnOnce(State, Request) -> Box<HandlerFuture> + Send + 'static
+        {
+            chain(state, req)
+        }
+
+        fn on_response(&self, _state: &mut State, _response: &mut Response) {
+            self.log.lock().unwrap().push(self.name);
+        }
+
+        fn on_finish(&self, _state: &mut State) {
+            self.log.lock().unwrap().push(self.name);
+        }
+    }
+
+    fn empty_handler(state: State, _req: Request) -> (State, Response) {
+        (state, Response::new().with_status(StatusCode::Ok))
+    }
+
+    #[test]
+    fn pipeline_hook_ordering_test() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let service_log = log.clone();
+
+        let new_service = NewHandlerService::new(move || {
+            let pipeline = new_pipeline()
+                .add(Recorder { name: "one", log: service_log.clone() })
+                .add(Recorder { name: "two", log: service_log.clone() })
+                .add(Recorder { name: "three", log: service_log.clone() })
+                .build();
+            Ok(move |state, req| pipeline.call(&|| Ok(empty_handler), state, req))
+        });
+
+        let uri = "http://localhost/".parse().unwrap();
+
+        let mut test_server = TestServer::new(new_service).unwrap();
+        let response = test_server.client("127.0.0.1:0".parse().unwrap()).unwrap().get(uri);
+        test_server.run_request(response).unwrap();
+
+        // `on_response` and `on_finish` run back-to-back for each `Middleware` as the future
+        // unwinds, in reverse order: the middleware closest to the handler ("three") sees the
+        // response and finishes first, and the outermost middleware ("one") runs last.
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec!["three", "three", "two", "two", "one", "one"]
+        );
+    }
 }
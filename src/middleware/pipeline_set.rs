@@ -0,0 +1,232 @@
+//! Defines types for combining several `Pipeline` values into a `PipelineSet`, and for selecting
+//! a subset of them to run for an individual route.
+//!
+//! A `Router` typically wants different groups of routes to run through different combinations of
+//! middleware &mdash; for example, a `global` pipeline that every route runs through for logging,
+//! and an `authenticated` pipeline that only some routes additionally require. `PipelineSet` lets
+//! several independently defined `Pipeline` values be built once up front and then referenced,
+//! via cheap `Handle` values, from each route.
+
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use handler::HandlerFuture;
+use hyper::server::Request;
+use middleware::pipeline::{NewPipelineInstance, Pipeline};
+use state::State;
+
+/// Marks the first element of some recursive tuple storage as the one being looked up.
+///
+/// This, together with `There`, lets `PipelineLookup` locate a particular element of a
+/// `PipelineSet`'s underlying storage regardless of how many other pipelines were registered
+/// before or after it.
+#[doc(hidden)]
+pub struct Here {
+    _marker: PhantomData<()>,
+}
+
+/// Marks an element of some recursive tuple storage which is one position further along than
+/// `N`.
+#[doc(hidden)]
+pub struct There<N> {
+    _marker: PhantomData<N>,
+}
+
+/// A recursive type used to locate a single `Pipeline<T>` within a `PipelineSet`'s underlying
+/// storage, regardless of how many other pipelines it was registered alongside.
+///
+/// This type should never be implemented outside of Gotham, does not form part of the public
+/// API, and is subject to change without notice.
+#[doc(hidden)]
+pub unsafe trait PipelineLookup<T, N> {
+    /// Borrows the `Pipeline<T>` out of this recursive storage.
+    fn borrow_pipeline(&self) -> &Pipeline<T>;
+}
+
+unsafe impl<T, Tail> PipelineLookup<T, Here> for (Pipeline<T>, Tail)
+    where T: NewPipelineInstance
+{
+    fn borrow_pipeline(&self) -> &Pipeline<T> {
+        &self.0
+    }
+}
+
+unsafe impl<Head, T, Tail, N> PipelineLookup<T, There<N>> for (Head, Tail)
+    where Tail: PipelineLookup<T, N>
+{
+    fn borrow_pipeline(&self) -> &Pipeline<T> {
+        self.1.borrow_pipeline()
+    }
+}
+
+/// A cheap, `Copy`-able reference to a single `Pipeline` previously registered in a
+/// `PipelineSet`.
+///
+/// `Handle` values are handed back by `PipelineSetBuilder::add`, and combined into a
+/// `PipelineHandleChain` (a tuple of `Handle` values, terminated by `()`) to select which
+/// pipelines run for an individual route.
+///
+/// The `N` parameter pins down exactly where the `Pipeline<T>` this `Handle` refers to sits
+/// within the final `PipelineSet`'s storage (see `PipelineLookup`). It's fixed the moment the
+/// `Handle` is created, and `PipelineSetBuilder::add` only ever appends new pipelines after the
+/// ones already registered, so it stays correct no matter how many more pipelines are added to
+/// the set afterwards &mdash; even another `Pipeline<T>` of the very same type.
+pub struct Handle<T, N>
+    where T: NewPipelineInstance
+{
+    phantom: PhantomData<(T, N)>,
+}
+
+impl<T, N> Clone for Handle<T, N>
+    where T: NewPipelineInstance
+{
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T, N> Copy for Handle<T, N> where T: NewPipelineInstance {}
+
+/// Holds a fixed collection of `Pipeline` values, built once up front via `PipelineSetBuilder`,
+/// and addressed by the `Handle` values handed out when they were added.
+///
+/// `PipelineSet` is cheaply `Clone`-able (it's a thin wrapper around an `Arc`), so it can be
+/// shared across all of a `Router`'s routes.
+pub struct PipelineSet<P> {
+    pipelines: Arc<P>,
+}
+
+impl<P> Clone for PipelineSet<P> {
+    fn clone(&self) -> Self {
+        PipelineSet { pipelines: self.pipelines.clone() }
+    }
+}
+
+/// Begins defining a new `PipelineSet`.
+///
+/// See `PipelineSetBuilder` for information on using `new_pipeline_set()`.
+pub fn new_pipeline_set() -> PipelineSetBuilder<()> {
+    PipelineSetBuilder { t: () }
+}
+
+/// Finalizes a `PipelineSetBuilder`, producing the `PipelineSet` that a `Router` is built
+/// against.
+pub fn finalize_pipeline_set<P>(builder: PipelineSetBuilder<P>) -> PipelineSet<P> {
+    PipelineSet { pipelines: Arc::new(builder.t) }
+}
+
+/// Allows a `PipelineSet` to be defined by adding built `Pipeline` values, handing back a
+/// `Handle` for each one so it can be referenced later from route definitions.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let (pipelines, global) = new_pipeline_set()
+///     .add(new_pipeline().add(LoggingMiddleware).build());
+///
+/// let (pipelines, auth) = pipelines
+///     .add(new_pipeline().add(AuthMiddleware).build());
+///
+/// let pipelines = finalize_pipeline_set(pipelines);
+///
+/// // `(global, (auth, ()))` runs the logging pipeline, then the auth pipeline, for a route.
+/// ```
+pub struct PipelineSetBuilder<P> {
+    t: P,
+}
+
+impl<P> PipelineSetBuilder<P> {
+    /// Adds a built `Pipeline` to the set, returning the updated builder along with a `Handle`
+    /// which can be used to select this pipeline from a route.
+    pub fn add<T>(self, p: Pipeline<T>) -> (PipelineSetBuilder<P::Output>, Handle<T, P::Index>)
+        where T: NewPipelineInstance,
+              P: PipelineAppend<T>
+    {
+        (PipelineSetBuilder { t: self.t.append(p) }, Handle { phantom: PhantomData })
+    }
+}
+
+/// Appends a `Pipeline<T>` to the end of some recursive tuple storage, without disturbing the
+/// position of anything already stored there.
+///
+/// Unlike simply consing the new `Pipeline` onto the front, appending to the end means a
+/// `Handle`'s `N` (fixed at the time `PipelineSetBuilder::add` hands it back) remains valid no
+/// matter how many more pipelines are added to the set afterwards.
+///
+/// This type should never be implemented outside of Gotham, does not form part of the public
+/// API, and is subject to change without notice.
+#[doc(hidden)]
+pub unsafe trait PipelineAppend<T>
+    where T: NewPipelineInstance
+{
+    /// The storage type once `Pipeline<T>` has been appended.
+    type Output;
+
+    /// The position `Pipeline<T>` ends up at within `Output`, for use as a `Handle`'s `N`.
+    type Index;
+
+    /// Appends `p` to the end of this storage.
+    fn append(self, p: Pipeline<T>) -> Self::Output;
+}
+
+unsafe impl<T> PipelineAppend<T> for ()
+    where T: NewPipelineInstance
+{
+    type Output = (Pipeline<T>, ());
+    type Index = Here;
+
+    fn append(self, p: Pipeline<T>) -> Self::Output {
+        (p, ())
+    }
+}
+
+unsafe impl<Head, Tail, T> PipelineAppend<T> for (Head, Tail)
+    where T: NewPipelineInstance,
+          Tail: PipelineAppend<T>
+{
+    type Output = (Head, Tail::Output);
+    type Index = There<Tail::Index>;
+
+    fn append(self, p: Pipeline<T>) -> Self::Output {
+        let (head, tail) = self;
+        (head, tail.append(p))
+    }
+}
+
+/// A recursive type representing an ordered selection of pipelines from a `PipelineSet`, used to
+/// process a single request before it reaches the route's own `Handler`.
+///
+/// This type should never be implemented outside of Gotham, does not form part of the public
+/// API, and is subject to change without notice.
+#[doc(hidden)]
+pub unsafe trait PipelineHandleChain<P> {
+    /// Threads `state` and `request` through each selected pipeline, in order, before invoking
+    /// `f`.
+    fn call<F>(&self, pipelines: &PipelineSet<P>, state: State, request: Request, f: F) -> Box<HandlerFuture>
+        where F: FnOnce(State, Request) -> Box<HandlerFuture> + Send + 'static;
+}
+
+unsafe impl<P> PipelineHandleChain<P> for () {
+    fn call<F>(&self, _pipelines: &PipelineSet<P>, state: State, request: Request, f: F) -> Box<HandlerFuture>
+        where F: FnOnce(State, Request) -> Box<HandlerFuture> + Send + 'static
+    {
+        // () marks the end of the chain, so the provided function is invoked directly.
+        f(state, request)
+    }
+}
+
+unsafe impl<P, T, N, Rest> PipelineHandleChain<P> for (Handle<T, N>, Rest)
+    where T: NewPipelineInstance,
+          T::Instance: Send + 'static,
+          P: PipelineLookup<T, N>,
+          Rest: PipelineHandleChain<P>
+{
+    fn call<F>(&self, pipelines: &PipelineSet<P>, state: State, request: Request, f: F) -> Box<HandlerFuture>
+        where F: FnOnce(State, Request) -> Box<HandlerFuture> + Send + 'static
+    {
+        let (_, ref rest) = *self;
+        let pipeline = pipelines.pipelines.borrow_pipeline();
+        let pipelines = pipelines.clone();
+        pipeline.call_internal(state, request, move |state, req| rest.call(&pipelines, state, req, f))
+    }
+}
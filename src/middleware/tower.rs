@@ -0,0 +1,267 @@
+//! Bridges Gotham's `Middleware`/`NewMiddleware` with `tower::Layer`/`tower::Service`, so the
+//! wider tower middleware ecosystem (retry, timeout, load-shed, tracing, ...) can be used inside
+//! a Gotham `Pipeline` without being reimplemented against the native traits.
+//!
+//! This module is only compiled when the `tower-compat` feature is enabled.
+
+use std::io;
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use futures::{future, Async, Future, Poll};
+use hyper::server::{Request, Response};
+use tower::{Layer, Service};
+
+use handler::{HandlerFuture, NewHandler};
+use middleware::{Middleware, NewMiddleware};
+use middleware::pipeline::{NewPipelineInstance, Pipeline, PipelineInstance};
+use state::State;
+
+/// The remainder of a Gotham `Pipeline` (the `chain` closure passed to `Middleware::call`),
+/// type-erased to a single concrete type so that `ChainService` &mdash; and therefore the
+/// `Layer` bound on `TowerMiddleware` &mdash; doesn't need to be generic over the `Chain` type
+/// parameter of `Middleware::call`.
+type BoxChain = Box<FnOnce(State, Request) -> Box<HandlerFuture> + Send>;
+
+/// Adapts a `tower::Layer` into a Gotham `NewMiddleware`, so it can be added to a `Pipeline`
+/// alongside native Gotham `Middleware`.
+///
+/// The remainder of the `Pipeline` (everything from this point on, including the eventual
+/// `Handler`) is presented to the `Layer` as its inner `tower::Service`, via `ChainService`. A
+/// fresh tower `Service` is therefore built from the `Layer` for every request, mirroring the way
+/// Gotham already builds a fresh `Middleware` instance per request from a `NewMiddleware`.
+pub struct TowerMiddleware<L> {
+    layer: Arc<L>,
+}
+
+impl<L> TowerMiddleware<L> {
+    /// Wraps a `tower::Layer` so that it can be added to a `Pipeline` as Gotham middleware.
+    pub fn new(layer: L) -> TowerMiddleware<L> {
+        TowerMiddleware { layer: Arc::new(layer) }
+    }
+}
+
+impl<L> Clone for TowerMiddleware<L> {
+    fn clone(&self) -> Self {
+        TowerMiddleware { layer: self.layer.clone() }
+    }
+}
+
+impl<L> NewMiddleware for TowerMiddleware<L>
+    where L: Layer<ChainService, Request = Request, Response = Response, Error = io::Error>,
+          L: Sync + Send + 'static,
+          L::Service: Send + 'static,
+          <L::Service as Service>::Future: Send + 'static
+{
+    type Instance = TowerMiddleware<L>;
+    type Error = io::Error;
+
+    fn new_middleware(&self) -> Result<TowerMiddleware<L>, io::Error> {
+        Ok(self.clone())
+    }
+}
+
+impl<L> Middleware for TowerMiddleware<L>
+    where L: Layer<ChainService, Request = Request, Response = Response, Error = io::Error>,
+          L: Send + 'static,
+          L::Service: Send + 'static,
+          <L::Service as Service>::Future: Send + 'static
+{
+    fn call<Chain>(&self, state: State, req: Request, chain: Chain) -> Box<HandlerFuture>
+        where Chain: FnOnce(State, Request) -> Box<HandlerFuture> + Send + 'static
+    {
+        let inner = ChainService {
+            state: Arc::new(Mutex::new(Some(state))),
+            chain: Arc::new(Mutex::new(Some(Box::new(chain) as BoxChain))),
+        };
+        let state = inner.state.clone();
+
+        let service = self.layer.layer(inner);
+
+        Box::new(ReadyAndCall::new(service, req).then(move |result| {
+            let state = state.lock().unwrap().take().expect("state stashed by ChainService");
+            match result {
+                Ok(response) => future::ok((state, response)),
+                Err(e) => future::err((state, e.into())),
+            }
+        }))
+    }
+}
+
+/// Drives a `tower::Service` to readiness before calling it, honouring the readiness contract
+/// that `poll_ready` must return `Async::Ready(())` before `call` may be invoked.
+enum ReadyAndCall<S>
+    where S: Service
+{
+    Waiting(S, Option<S::Request>),
+    Calling(S::Future),
+    Done,
+}
+
+impl<S> ReadyAndCall<S>
+    where S: Service
+{
+    fn new(service: S, req: S::Request) -> ReadyAndCall<S> {
+        ReadyAndCall::Waiting(service, Some(req))
+    }
+}
+
+impl<S> Future for ReadyAndCall<S>
+    where S: Service
+{
+    type Item = S::Response;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            // Polled in place, rather than via the `mem::replace` below, so that a `NotReady`
+            // from the inner future leaves it right where it was instead of dropping it.
+            if let ReadyAndCall::Calling(ref mut fut) = *self {
+                return fut.poll();
+            }
+
+            if let ReadyAndCall::Waiting(ref mut service, _) = *self {
+                match service.poll_ready() {
+                    Ok(Async::Ready(())) => (),
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(e) => return Err(e),
+                }
+            }
+
+            *self = match mem::replace(self, ReadyAndCall::Done) {
+                ReadyAndCall::Waiting(mut service, req) => {
+                    let req = req.expect("ReadyAndCall::Waiting always holds a request");
+                    ReadyAndCall::Calling(service.call(req))
+                }
+                ReadyAndCall::Calling(_) => unreachable!("handled above"),
+                ReadyAndCall::Done => panic!("ReadyAndCall polled after completion"),
+            };
+        }
+    }
+}
+
+/// Wraps the remainder of a Gotham `Pipeline` (the `chain` closure passed to
+/// `Middleware::call`) as a `tower::Service<Request>`, so it can sit as the innermost service
+/// underneath a layered `tower::Layer`.
+///
+/// `tower::Service` only knows about `Request`/`Response`; it has no notion of Gotham's `State`.
+/// `ChainService` stashes the `State` that travels alongside the `Request` before handing off
+/// into tower, and restores it once the wrapped future settles, so `TowerMiddleware::call` can
+/// hand a `(State, Response)` pair back to the rest of the `Pipeline`.
+///
+/// The wrapped `chain` represents the rest of a single Gotham request's `Pipeline`, and can only
+/// be driven once: a second `call` &mdash; as a retry or reconnect `Layer` would attempt after a
+/// failure &mdash; is rejected with an error rather than panicking, since Gotham has no way to
+/// run the remainder of a `Pipeline` for the same request twice.
+pub struct ChainService {
+    state: Arc<Mutex<Option<State>>>,
+    chain: Arc<Mutex<Option<BoxChain>>>,
+}
+
+impl Service for ChainService {
+    type Request = Request;
+    type Response = Response;
+    type Error = io::Error;
+    type Future = Box<Future<Item = Response, Error = io::Error> + Send>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        // Checked (and taken) before `state`, so a rejected second call leaves `state` exactly
+        // as it was left by the first -- otherwise `TowerMiddleware::call`'s `.then` would find
+        // no stashed `State` to restore once this error future resolves.
+        let chain = match self.chain.lock().unwrap().take() {
+            Some(chain) => chain,
+            None => {
+                return Box::new(future::err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "ChainService can only run the rest of a Gotham Pipeline once per request; \
+                     a Layer that retries or reconnects must not call the inner Service again",
+                )));
+            }
+        };
+        let state = self.state
+            .lock()
+            .unwrap()
+            .take()
+            .expect("state stashed alongside chain, and taken only together with it");
+
+        let state_out = self.state.clone();
+        Box::new(chain(state, req).then(move |result| {
+            match result {
+                Ok((state, response)) => {
+                    *state_out.lock().unwrap() = Some(state);
+                    future::ok(response)
+                }
+                Err((state, e)) => {
+                    *state_out.lock().unwrap() = Some(state);
+                    future::err(io::Error::new(io::ErrorKind::Other, e))
+                }
+            }
+        }))
+    }
+}
+
+/// Exposes a built `Pipeline` (plus the `NewHandler` it ultimately dispatches to) as a
+/// `tower::Service<Request>`, so an entire Gotham middleware stack can be embedded in a
+/// tower-based server instead of Gotham's own.
+pub struct PipelineService<T, H>
+    where T: NewPipelineInstance
+{
+    pipeline: Arc<Pipeline<T>>,
+    new_handler: Arc<H>,
+}
+
+impl<T, H> PipelineService<T, H>
+    where T: NewPipelineInstance
+{
+    /// Wraps a built `Pipeline` and its `NewHandler` as a `tower::Service`.
+    pub fn new(pipeline: Pipeline<T>, new_handler: H) -> PipelineService<T, H> {
+        PipelineService {
+            pipeline: Arc::new(pipeline),
+            new_handler: Arc::new(new_handler),
+        }
+    }
+}
+
+impl<T, H> Clone for PipelineService<T, H>
+    where T: NewPipelineInstance
+{
+    fn clone(&self) -> Self {
+        PipelineService {
+            pipeline: self.pipeline.clone(),
+            new_handler: self.new_handler.clone(),
+        }
+    }
+}
+
+impl<T, H> Service for PipelineService<T, H>
+    where T: NewPipelineInstance,
+          T::Instance: PipelineInstance,
+          H: NewHandler + 'static,
+          H::Instance: 'static
+{
+    type Request = Request;
+    type Response = Response;
+    type Error = io::Error;
+    type Future = Box<Future<Item = Response, Error = io::Error> + Send>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        // Mirrors the `State` that Gotham's own hyper-facing service creates for each incoming
+        // request, before handing it to a `Pipeline`.
+        let state = State::new();
+
+        Box::new(self.pipeline.call(&*self.new_handler, state, req).then(|result| {
+            match result {
+                Ok((_state, response)) => future::ok(response),
+                Err((_state, e)) => future::err(io::Error::new(io::ErrorKind::Other, e)),
+            }
+        }))
+    }
+}